@@ -1,17 +1,16 @@
-#[macro_use]
-extern crate itertools;
-use core::ops::Deref;
 use image::DynamicImage;
 use image::{GenericImageView, ImageBuffer, Pixel, Rgba, RgbaImage};
 use rand::distributions::Standard;
 use rand::prelude::*;
-use std::convert::TryInto;
+use rand::rngs::StdRng;
+use rayon::prelude::*;
 use std::num::Wrapping;
 use std::path::PathBuf;
 use structopt::StructOpt;
 
-#[derive(StructOpt, Debug)]
+#[derive(StructOpt, Debug, Clone)]
 /// An image rustifier - mainly a parallel implementation of corruster
+#[allow(clippy::upper_case_acronyms)]
 struct CLI {
     /// The image to be "corrupted" by the program
     #[structopt(parse(from_os_str))]
@@ -55,6 +54,191 @@ struct CLI {
     /// Standard deviation of the chromatic abberation offset (lower values induce longer trails)
     #[structopt(default_value = "1")]
     std_abberation: u32,
+    /// Number of fractal Brownian motion layers used by the turbulence warp
+    #[structopt(default_value = "4")]
+    octaves: u32,
+    /// Spatial frequency of the turbulence noise field
+    #[structopt(default_value = "0.01")]
+    frequency: f64,
+    /// Strength of the turbulence-driven pixel warp
+    #[structopt(default_value = "20")]
+    warp_magnitude: f64,
+    /// Take the absolute value of each noise octave (Flash-style turbulence) instead of signed Perlin noise
+    #[structopt(long)]
+    turbulence: bool,
+    /// Warp pixels along a fractal Perlin noise field instead of dissolve_block's salt-and-pepper block jitter
+    #[structopt(long)]
+    warp: bool,
+    /// Seed driving every corruption stage; a random one is drawn (and printed) if unset
+    #[structopt(long)]
+    seed: Option<u64>,
+    /// Number of frames to render; values above 1 produce a numbered sequence (or a GIF with `--gif`)
+    #[structopt(long, default_value = "1")]
+    frames: u32,
+    /// Encode a multi-frame render as an animated GIF instead of a numbered image sequence
+    #[structopt(long)]
+    gif: bool,
+    /// Final block offset to interpolate toward over the animation (ramps from `block_offset` when set)
+    #[structopt(long)]
+    block_offset_end: Option<u32>,
+    /// Final scanline lag strength to interpolate toward over the animation (ramps from `lag` when set)
+    #[structopt(long)]
+    lag_end: Option<f64>,
+    /// Final mean chromatic abberation to interpolate toward over the animation (ramps from `mean_abberation` when set)
+    #[structopt(long)]
+    mean_abberation_end: Option<u32>,
+    /// Shift only the chroma (U/V) planes of a YUV conversion instead of picking raw RGB channels
+    #[structopt(long)]
+    chroma_shift: bool,
+    /// Vertical mean chroma displacement in chroma-shift mode (defaults to `mean_abberation`)
+    #[structopt(long)]
+    mean_abberation_y: Option<u32>,
+    /// Vertical standard deviation of the chroma displacement in chroma-shift mode (defaults to `std_abberation`)
+    #[structopt(long)]
+    std_abberation_y: Option<u32>,
+    /// Number of colors in the quantized palette; when set, a median-cut palette is built and the buffer is remapped to it as the last corruption stage, before compositing
+    #[structopt(long)]
+    palette_size: Option<u32>,
+    /// Whether to Floyd–Steinberg dither the palette remap, rather than a flat nearest-color snap
+    #[structopt(long, default_value = "on")]
+    dither: DitherMode,
+    /// Blend mode used to composite the corrupted buffer back over the pristine original
+    #[structopt(long, default_value = "normal")]
+    blend_mode: BlendMode,
+    /// Opacity of the corrupted layer when compositing over the original, in [0, 1]
+    #[structopt(long, default_value = "1.0")]
+    opacity: f64,
+    /// Reconstruction filter used to resample the fractional coordinates produced by the offset-based stages
+    #[structopt(long, default_value = "triangle")]
+    filter: FilterKernel,
+    /// Radius, in source pixels, of the gaussian reconstruction filter's sampling footprint
+    #[structopt(long, default_value = "2")]
+    filter_radius: i64,
+    /// Standard deviation of the gaussian reconstruction filter
+    #[structopt(long, default_value = "1.0")]
+    filter_sigma: f64,
+}
+
+/// Selects the reconstruction kernel `sample_pixel` uses to resample a
+/// fractional source coordinate: a single nearest tap, bilinear, or a
+/// windowed Gaussian over a wider, configurable footprint
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FilterKernel {
+    Box,
+    Triangle,
+    Gaussian,
+}
+
+impl std::str::FromStr for FilterKernel {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "box" => Ok(FilterKernel::Box),
+            "triangle" => Ok(FilterKernel::Triangle),
+            "gaussian" => Ok(FilterKernel::Gaussian),
+            other => Err(format!(
+                "invalid filter kernel `{}` (expected one of box, triangle, gaussian)",
+                other
+            )),
+        }
+    }
+}
+
+/// Selects how the corrupted buffer is composited back over the pristine
+/// original in `Corrupter::composite`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BlendMode {
+    Normal,
+    Multiply,
+    Screen,
+    Overlay,
+    Difference,
+    Add,
+}
+
+impl std::str::FromStr for BlendMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "normal" => Ok(BlendMode::Normal),
+            "multiply" => Ok(BlendMode::Multiply),
+            "screen" => Ok(BlendMode::Screen),
+            "overlay" => Ok(BlendMode::Overlay),
+            "difference" => Ok(BlendMode::Difference),
+            "add" => Ok(BlendMode::Add),
+            other => Err(format!(
+                "invalid blend mode `{}` (expected one of normal, multiply, screen, overlay, difference, add)",
+                other
+            )),
+        }
+    }
+}
+
+/// Blends a single 8-bit channel pair under the given `BlendMode`, working in
+/// normalized `[0, 1]` space
+fn blend_channel(mode: BlendMode, base: u8, top: u8) -> u8 {
+    let (a, b) = (base as f64 / 255., top as f64 / 255.);
+    let result = match mode {
+        BlendMode::Normal => b,
+        BlendMode::Multiply => a * b,
+        BlendMode::Screen => 1. - (1. - a) * (1. - b),
+        BlendMode::Overlay => {
+            if a < 0.5 {
+                2. * a * b
+            } else {
+                1. - 2. * (1. - a) * (1. - b)
+            }
+        }
+        BlendMode::Difference => (a - b).abs(),
+        BlendMode::Add => (a + b).min(1.),
+    };
+    (result * 255.).round().clamp(0., 255.) as u8
+}
+
+/// Linearly interpolates two 8-bit channels by `t` in `[0, 1]`
+fn lerp_u8(a: u8, b: u8, t: f64) -> u8 {
+    (a as f64 + (b as f64 - a as f64) * t)
+        .round()
+        .clamp(0., 255.) as u8
+}
+
+/// Whether `quantize` diffuses its quantization error across neighboring
+/// pixels (Floyd–Steinberg) or simply snaps each pixel to its nearest
+/// palette color
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum DitherMode {
+    On,
+    Off,
+}
+
+impl std::str::FromStr for DitherMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "on" => Ok(DitherMode::On),
+            "off" => Ok(DitherMode::Off),
+            other => Err(format!("invalid dither mode `{}` (expected \"on\" or \"off\")", other)),
+        }
+    }
+}
+
+/// Linearly interpolates an optionally-ramped `u32` parameter across a `[0, 1]` animation progress `t`
+fn ramped_u32(start: u32, end: Option<u32>, t: f64) -> u32 {
+    match end {
+        Some(end) => (start as f64 + (end as f64 - start as f64) * t).round() as u32,
+        None => start,
+    }
+}
+
+/// Linearly interpolates an optionally-ramped `f64` parameter across a `[0, 1]` animation progress `t`
+fn ramped_f64(start: f64, end: Option<f64>, t: f64) -> f64 {
+    match end {
+        Some(end) => start + (end - start) * t,
+        None => start,
+    }
 }
 
 /// Dummy structure holding the four dimensiosn of the input image
@@ -71,23 +255,99 @@ struct Corrupter {
     bounds: Bounds,
     img: DynamicImage,
     buffer: RgbaImage,
+    /// Whether a buffer-producing stage has already run; once true, the next
+    /// stage samples from `buffer` (the previous stage's output) rather than
+    /// the pristine `img`, so the stages chain and their effects stack
+    /// instead of each one discarding the last
+    primed: bool,
+}
+
+/// Wraps a (possibly negative) pixel index toroidally into `[0, size)`
+fn wrap_index(i: i64, size: u32) -> u32 {
+    i.rem_euclid(size as i64) as u32
+}
+
+/// Weighs a sample at `distance` source pixels from the reconstruction
+/// center under the given `FilterKernel`
+fn filter_weight(kernel: FilterKernel, distance: f64, radius: f64, sigma: f64) -> f64 {
+    match kernel {
+        FilterKernel::Box => 1.0,
+        FilterKernel::Triangle => (1.0 - distance.abs()).max(0.0),
+        FilterKernel::Gaussian => {
+            if distance.abs() > radius {
+                0.0
+            } else {
+                (-distance * distance / (2.0 * sigma * sigma)).exp()
+            }
+        }
+    }
 }
 
-/// The primary way we are going to shift color channel
-fn modified_pixel(coord: u32, offset_coord: u32, bounds_coord: u32) -> u32 {
-    ((Wrapping(coord.clone()) + Wrapping(offset_coord.clone())).0 as i64
-        % bounds_coord.clone() as i64)
-        .try_into()
-        .unwrap()
+/// Resamples `img` at the fractional coordinate `(x, y)`, wrapping
+/// toroidally on both axes. Splits the coordinate into integer + fractional
+/// parts, gathers the neighboring sample footprint for `cfg.filter` (a single
+/// tap for box, 2×2 for triangle/bilinear, wider for gaussian), weights each neighbor by the
+/// filter applied to its distance, and normalizes the accumulated RGBA
+fn sample_pixel<I: GenericImageView<Pixel = Rgba<u8>>>(
+    img: &I,
+    x: f64,
+    y: f64,
+    bounds: Bounds,
+    cfg: &CLI,
+) -> Rgba<u8> {
+    let radius = match cfg.filter {
+        FilterKernel::Box => 0.5,
+        FilterKernel::Triangle => 1.0,
+        FilterKernel::Gaussian => cfg.filter_radius as f64,
+    };
+    let x0 = x.floor();
+    let y0 = y.floor();
+    let lo = (-radius).ceil() as i64;
+    let hi = radius.floor() as i64;
+
+    let mut acc = [0f64; 4];
+    let mut weight_sum = 0f64;
+    for dy in lo..=hi {
+        for dx in lo..=hi {
+            let sample_x = x0 + dx as f64;
+            let sample_y = y0 + dy as f64;
+            let weight = filter_weight(cfg.filter, x - sample_x, radius, cfg.filter_sigma)
+                * filter_weight(cfg.filter, y - sample_y, radius, cfg.filter_sigma);
+            if weight <= 0.0 {
+                continue;
+            }
+            let px = wrap_index(sample_x as i64, bounds.x_max);
+            let py = wrap_index(sample_y as i64, bounds.y_max);
+            let [r, g, b, a] = img.get_pixel(px, py).to_rgba().data;
+            acc[0] += r as f64 * weight;
+            acc[1] += g as f64 * weight;
+            acc[2] += b as f64 * weight;
+            acc[3] += a as f64 * weight;
+            weight_sum += weight;
+        }
+    }
+
+    if weight_sum <= 0.0 {
+        let px = wrap_index(x0 as i64, bounds.x_max);
+        let py = wrap_index(y0 as i64, bounds.y_max);
+        return img.get_pixel(px, py).to_rgba();
+    }
+    Rgba([
+        (acc[0] / weight_sum).round().clamp(0., 255.) as u8,
+        (acc[1] / weight_sum).round().clamp(0., 255.) as u8,
+        (acc[2] / weight_sum).round().clamp(0., 255.) as u8,
+        (acc[3] / weight_sum).round().clamp(0., 255.) as u8,
+    ])
 }
 
 /// A map between the magnitude and the pixel spatial shift
-fn offset<T, S>(rng: &mut ThreadRng, magnitude: S) -> T
+fn offset<T, S, R>(rng: &mut R, magnitude: S) -> T
 where
     rand::distributions::Standard: rand::distributions::Distribution<T>,
     T: std::convert::From<S>,
     Wrapping<T>: std::ops::Mul<Output = Wrapping<T>>,
     S: std::convert::Into<T>,
+    R: Rng + ?Sized,
 {
     let random = rng.sample::<T, _>(Standard);
     (Wrapping(random) * Wrapping(magnitude.into())).0
@@ -99,9 +359,201 @@ fn brighten_pixels(pixel: u8, brighteness_addition: u8) -> u8 {
     .0
 }
 
+/// Converts an RGB triple to BT.601 luma/chroma: `Y = 0.299R + 0.587G + 0.114B`,
+/// with `U`/`V` the scaled blue/red differences against that luma
+fn rgb_to_yuv(r: u8, g: u8, b: u8) -> (f64, f64, f64) {
+    let (r, g, b) = (r as f64, g as f64, b as f64);
+    let y = 0.299 * r + 0.587 * g + 0.114 * b;
+    let u = 0.492111 * (b - y);
+    let v = 0.877283 * (r - y);
+    (y, u, v)
+}
+
+/// Inverse of `rgb_to_yuv`, clamped back into the `[0, 255]` channel range
+fn yuv_to_rgb(y: f64, u: f64, v: f64) -> [u8; 3] {
+    let r = y + 1.13983 * v;
+    let g = y - 0.39465 * u - 0.58060 * v;
+    let b = y + 2.03211 * u;
+    [
+        r.clamp(0., 255.) as u8,
+        g.clamp(0., 255.) as u8,
+        b.clamp(0., 255.) as u8,
+    ]
+}
+
+/// A bucket of pixels used while building a median-cut palette
+struct ColorBox {
+    pixels: Vec<[i32; 3]>,
+}
+
+impl ColorBox {
+    /// Returns the channel with the widest value range in this box, and that range
+    fn widest_channel(&self) -> (usize, i32) {
+        let mut widest = (0, -1);
+        for channel in 0..3 {
+            let min = self.pixels.iter().map(|p| p[channel]).min().unwrap_or(0);
+            let max = self.pixels.iter().map(|p| p[channel]).max().unwrap_or(0);
+            if max - min > widest.1 {
+                widest = (channel, max - min);
+            }
+        }
+        widest
+    }
+
+    fn average_color(&self) -> [u8; 3] {
+        let len = self.pixels.len().max(1) as i64;
+        let mut sum = [0i64; 3];
+        for pixel in &self.pixels {
+            for channel in 0..3 {
+                sum[channel] += pixel[channel] as i64;
+            }
+        }
+        [
+            (sum[0] / len) as u8,
+            (sum[1] / len) as u8,
+            (sum[2] / len) as u8,
+        ]
+    }
+}
+
+/// Builds an N-color palette by repeatedly splitting the box with the
+/// largest channel range along its widest channel at the median, until
+/// `palette_size` boxes remain, then averaging each box's pixels
+fn median_cut_palette(pixels: &[[i32; 3]], palette_size: usize) -> Vec<[u8; 3]> {
+    let mut boxes = vec![ColorBox {
+        pixels: pixels.to_vec(),
+    }];
+
+    while boxes.len() < palette_size {
+        let widest_idx = boxes
+            .iter()
+            .enumerate()
+            .max_by_key(|(_, b)| b.widest_channel().1)
+            .map(|(i, _)| i)
+            .unwrap();
+
+        if boxes[widest_idx].pixels.len() < 2 {
+            break;
+        }
+
+        let (channel, _) = boxes[widest_idx].widest_channel();
+        let mut split_box = boxes.swap_remove(widest_idx);
+        split_box.pixels.sort_by_key(|p| p[channel]);
+        let upper_half = split_box.pixels.split_off(split_box.pixels.len() / 2);
+        boxes.push(ColorBox {
+            pixels: split_box.pixels,
+        });
+        boxes.push(ColorBox { pixels: upper_half });
+    }
+
+    boxes.iter().map(ColorBox::average_color).collect()
+}
+
+/// Finds the palette color closest to `color` by squared RGB distance
+fn nearest_palette_color(color: [i32; 3], palette: &[[u8; 3]]) -> [u8; 3] {
+    palette
+        .iter()
+        .copied()
+        .min_by_key(|p| {
+            let dr = color[0] - p[0] as i32;
+            let dg = color[1] - p[1] as i32;
+            let db = color[2] - p[2] as i32;
+            dr * dr + dg * dg + db * db
+        })
+        .unwrap_or([0, 0, 0])
+}
+
+/// The eight gradient directions used by `Perlin`, spaced around the unit circle
+const GRADIENTS: [(f64, f64); 8] = [
+    (1.0, 0.0),
+    (-1.0, 0.0),
+    (0.0, 1.0),
+    (0.0, -1.0),
+    (std::f64::consts::FRAC_1_SQRT_2, std::f64::consts::FRAC_1_SQRT_2),
+    (-std::f64::consts::FRAC_1_SQRT_2, std::f64::consts::FRAC_1_SQRT_2),
+    (std::f64::consts::FRAC_1_SQRT_2, -std::f64::consts::FRAC_1_SQRT_2),
+    (-std::f64::consts::FRAC_1_SQRT_2, -std::f64::consts::FRAC_1_SQRT_2),
+];
+
+/// A classic Perlin noise field, used by `turbulence_warp` to produce coherent
+/// gradient noise instead of the purely-random jitter of `dissolve_block`
+struct Perlin {
+    perm: [u8; 512],
+}
+
+impl Perlin {
+    /// Builds a 256-entry permutation table shuffled by `rng`, duplicated to 512
+    /// entries so lookups never need to wrap the index by hand
+    fn new<R: Rng + ?Sized>(rng: &mut R) -> Self {
+        let mut table: Vec<u8> = (0..=255).collect();
+        table.shuffle(rng);
+        let mut perm = [0u8; 512];
+        for (i, slot) in perm.iter_mut().enumerate() {
+            *slot = table[i % 256];
+        }
+        Self { perm }
+    }
+
+    fn fade(t: f64) -> f64 {
+        t * t * t * (t * (t * 6.0 - 15.0) + 10.0)
+    }
+
+    fn lerp(t: f64, a: f64, b: f64) -> f64 {
+        a + t * (b - a)
+    }
+
+    fn grad(hash: u8, x: f64, y: f64) -> f64 {
+        let (gx, gy) = GRADIENTS[(hash & 7) as usize];
+        gx * x + gy * y
+    }
+
+    /// Evaluates a single octave of 2D Perlin noise at `(x, y)`
+    fn noise(&self, x: f64, y: f64) -> f64 {
+        let xi = (x.floor() as i64).rem_euclid(256) as usize;
+        let yi = (y.floor() as i64).rem_euclid(256) as usize;
+        let xf = x - x.floor();
+        let yf = y - y.floor();
+
+        let u = Self::fade(xf);
+        let v = Self::fade(yf);
+
+        let aa = self.perm[self.perm[xi] as usize + yi];
+        let ab = self.perm[self.perm[xi] as usize + yi + 1];
+        let ba = self.perm[self.perm[xi + 1] as usize + yi];
+        let bb = self.perm[self.perm[xi + 1] as usize + yi + 1];
+
+        let x1 = Self::lerp(u, Self::grad(aa, xf, yf), Self::grad(ba, xf - 1.0, yf));
+        let x2 = Self::lerp(
+            u,
+            Self::grad(ab, xf, yf - 1.0),
+            Self::grad(bb, xf - 1.0, yf - 1.0),
+        );
+        Self::lerp(v, x1, x2)
+    }
+
+    /// Sums `octaves` layers of noise, doubling frequency and halving amplitude
+    /// each octave; `turbulence` takes `abs()` of each octave (Flash-style turbulence)
+    fn fbm(&self, x: f64, y: f64, octaves: u32, turbulence: bool) -> f64 {
+        let mut total = 0.;
+        let mut frequency = 1.;
+        let mut amplitude = 1.;
+        let mut max_value = 0.;
+        for _ in 0..octaves {
+            let n = self.noise(x * frequency, y * frequency);
+            total += (if turbulence { n.abs() } else { n }) * amplitude;
+            max_value += amplitude;
+            amplitude *= 0.5;
+            frequency *= 2.;
+        }
+        total / max_value
+    }
+}
+
 impl Corrupter {
-    fn new(src_img: &PathBuf) -> Self {
-        let img = image::open(src_img).expect("No compatible image found");
+    /// Builds a `Corrupter` from an already-decoded image, so a `--frames`
+    /// animation can render every frame off the same decode instead of
+    /// re-reading the source file from disk each time
+    fn from_image(img: DynamicImage) -> Self {
         let (x_min, x_max, y_min, y_max) = (0, img.width(), 0, img.height());
         let bounds = Bounds {
             x_min,
@@ -113,157 +565,436 @@ impl Corrupter {
             bounds,
             img,
             buffer: ImageBuffer::new(bounds.x_max, bounds.y_max),
+            primed: false,
         }
     }
 
-    /// First stage of the corrupter
+    /// Returns the image the next buffer-producing stage should sample from:
+    /// the pristine `img` for the first stage, or a snapshot of `buffer` once
+    /// a previous stage has already primed it, so each stage builds on the
+    /// last instead of overwriting it from the untouched source
+    fn read_source(&self) -> RgbaImage {
+        if self.primed {
+            self.buffer.clone()
+        } else {
+            self.img.to_rgba()
+        }
+    }
+
+    /// First stage of the corrupter: dispatches to `turbulence_warp`'s smooth
+    /// Perlin smear when `cfg.warp` is set, otherwise `dissolve_block`'s
+    /// salt-and-pepper block jitter
+    fn distort<R: Rng + ?Sized>(&mut self, rng: &mut R, cfg: &CLI) -> &mut Self {
+        if cfg.warp {
+            self.turbulence_warp(rng, cfg)
+        } else {
+            self.dissolve_block(rng, cfg)
+        }
+    }
     /// Goes through the image and offset some pixel spatially by blocks
-    fn dissolve_block(&mut self, rng: &mut ThreadRng, cfg: &CLI) -> &mut Self {
+    ///
+    /// The block/stride/yset state that the original serial walk mutated as it
+    /// went is instead precomputed per row up front, so each row band can then
+    /// be rendered independently on rayon's thread pool
+    fn dissolve_block<R: Rng + ?Sized>(&mut self, rng: &mut R, cfg: &CLI) -> &mut Self {
+        let bounds = self.bounds;
+        let source = self.read_source();
+        let img = &source;
+        let row_stride = bounds.x_max as usize * 4;
+
         let mut line_offset = 0;
         let mut stride = 0.;
         let mut yset = 0;
+        let row_states: Vec<(i64, f64, u32)> = (bounds.y_min..bounds.y_max)
+            .map(|y| {
+                if rng.gen_ratio(cfg.block_height, bounds.x_max) {
+                    line_offset = offset::<i64, i64, _>(rng, cfg.block_offset.into());
+                    stride = cfg.stride_magnitude;
+                    yset = y;
+                }
+                (line_offset, stride, yset)
+            })
+            .collect();
+        let row_seeds: Vec<u64> = (bounds.y_min..bounds.y_max).map(|_| rng.gen()).collect();
 
-        for (x, y) in iproduct!(
-            self.bounds.x_min..self.bounds.x_max,
-            self.bounds.y_min..self.bounds.y_max
-        ) {
-            if rng.gen_ratio(cfg.block_height, self.bounds.x_max) {
-                line_offset = offset::<i64, i64>(rng, cfg.block_offset.into());
-                stride = cfg.stride_magnitude;
-                yset = y;
-            }
-            let stride_offset: i64 = (stride as u32 * (Wrapping(y) - Wrapping(yset)).0) as i64;
-            let offset_x = (Wrapping(offset::<i64, i64>(rng, cfg.magnitude))
-                + Wrapping(line_offset)
-                + Wrapping(stride_offset))
-            .0;
-            let offset_y = offset::<i64, i64>(rng, cfg.magnitude);
-            self.buffer.put_pixel(
-                x,
-                y,
-                self.img.get_pixel(
-                    modified_pixel(
-                        x,
-                        offset_x.try_into().unwrap_or(std::u32::MAX),
-                        self.bounds.x_max,
-                    ),
-                    modified_pixel(
-                        y,
-                        offset_y.try_into().unwrap_or(std::u32::MAX),
-                        self.bounds.y_max,
-                    ),
-                ),
-            );
-        }
+        self.buffer
+            .par_chunks_mut(row_stride)
+            .zip(row_states.par_iter())
+            .zip(row_seeds.par_iter())
+            .enumerate()
+            .for_each(|(row_idx, ((row, &(line_offset, stride, yset)), &seed))| {
+                let y = bounds.y_min + row_idx as u32;
+                let mut row_rng = StdRng::seed_from_u64(seed);
+                let stride_offset: i64 = (stride as u32 * (Wrapping(y) - Wrapping(yset)).0) as i64;
+                for x in bounds.x_min..bounds.x_max {
+                    let offset_x = (Wrapping(offset::<i64, i64, _>(&mut row_rng, cfg.magnitude))
+                        + Wrapping(line_offset)
+                        + Wrapping(stride_offset))
+                    .0;
+                    let offset_y = offset::<i64, i64, _>(&mut row_rng, cfg.magnitude);
+                    let pixel = sample_pixel(
+                        img,
+                        x as f64 + offset_x as f64,
+                        y as f64 + offset_y as f64,
+                        bounds,
+                        cfg,
+                    );
+                    let idx = x as usize * 4;
+                    row[idx..idx + 4].copy_from_slice(&pixel.data);
+                }
+            });
+        self.primed = true;
         self
     }
-    fn random_brightening(&mut self, rng: &mut ThreadRng, cfg: &CLI) -> &mut Self {
-        for (x, y) in iproduct!(
-            self.bounds.x_min..self.bounds.x_max,
-            self.bounds.y_min..self.bounds.y_max
-        ) {
-            let mut lr = cfg.lr;
-            let mut lg = cfg.lg;
-            let mut lb = cfg.lb;
-            lr += offset::<u32, u32>(rng, cfg.lr.into());
-            lg += offset::<u32, u32>(rng, cfg.lg.into());
-            lb += offset::<u32, u32>(rng, cfg.lb.into());
-            let offset_x = offset::<u32, u32>(rng, cfg.std_offset);
-            let [r, _, _, a] = self
-                .img
-                .get_pixel(
-                    modified_pixel((Wrapping(x) - Wrapping(lr)).0, offset_x, self.bounds.x_max),
-                    modified_pixel(0, y, self.bounds.y_max),
-                )
-                .to_rgba()
-                .data;
-
-            let (b, g) = (
-                self.img
-                    .get_pixel(
-                        modified_pixel(x, lg, self.bounds.x_max),
-                        modified_pixel(0, y, self.bounds.y_max),
-                    )
-                    .to_rgba()
-                    .data[1],
-                self.img
-                    .get_pixel(
-                        modified_pixel(x + lb, offset_x, self.bounds.x_max),
-                        modified_pixel(0, y, self.bounds.y_max),
-                    )
-                    .to_rgba()
-                    .data[2],
-            );
-            self.buffer.put_pixel(
-                x,
-                y,
-                Rgba([
-                    brighten_pixels(r, cfg.brighteness_addition),
-                    brighten_pixels(g, cfg.brighteness_addition),
-                    brighten_pixels(b, cfg.brighteness_addition),
-                    brighten_pixels(a, cfg.brighteness_addition),
-                ]),
-            );
+    /// Warps pixels along a fractal Perlin noise field instead of the
+    /// salt-and-pepper jitter of `dissolve_block`, giving a smooth flowing
+    /// smear/liquify distortion
+    fn turbulence_warp<R: Rng + ?Sized>(&mut self, rng: &mut R, cfg: &CLI) -> &mut Self {
+        let bounds = self.bounds;
+        let source = self.read_source();
+        let img = &source;
+        let row_stride = bounds.x_max as usize * 4;
+        let noise_x = Perlin::new(rng);
+        let noise_y = Perlin::new(rng);
+
+        self.buffer
+            .par_chunks_mut(row_stride)
+            .enumerate()
+            .for_each(|(row_idx, row)| {
+                let y = bounds.y_min + row_idx as u32;
+                for x in bounds.x_min..bounds.x_max {
+                    let nx = noise_x.fbm(
+                        x as f64 * cfg.frequency,
+                        y as f64 * cfg.frequency,
+                        cfg.octaves,
+                        cfg.turbulence,
+                    );
+                    let ny = noise_y.fbm(
+                        x as f64 * cfg.frequency,
+                        y as f64 * cfg.frequency,
+                        cfg.octaves,
+                        cfg.turbulence,
+                    );
+                    let offset_x = nx * cfg.warp_magnitude;
+                    let offset_y = ny * cfg.warp_magnitude;
+                    let pixel =
+                        sample_pixel(img, x as f64 + offset_x, y as f64 + offset_y, bounds, cfg);
+                    let idx = x as usize * 4;
+                    row[idx..idx + 4].copy_from_slice(&pixel.data);
+                }
+            });
+        self.primed = true;
+        self
+    }
+    fn random_brightening<R: Rng + ?Sized>(&mut self, rng: &mut R, cfg: &CLI) -> &mut Self {
+        let bounds = self.bounds;
+        let source = self.read_source();
+        let img = &source;
+        let row_stride = bounds.x_max as usize * 4;
+        let row_seeds: Vec<u64> = (bounds.y_min..bounds.y_max).map(|_| rng.gen()).collect();
+
+        self.buffer
+            .par_chunks_mut(row_stride)
+            .zip(row_seeds.par_iter())
+            .enumerate()
+            .for_each(|(row_idx, (row, &seed))| {
+                let y = bounds.y_min + row_idx as u32;
+                let mut row_rng = StdRng::seed_from_u64(seed);
+                for x in bounds.x_min..bounds.x_max {
+                    let mut lr = cfg.lr;
+                    let mut lg = cfg.lg;
+                    let mut lb = cfg.lb;
+                    lr += offset::<u32, u32, _>(&mut row_rng, (cfg.lr as f64 * cfg.lag).round() as u32);
+                    lg += offset::<u32, u32, _>(&mut row_rng, (cfg.lg as f64 * cfg.lag).round() as u32);
+                    lb += offset::<u32, u32, _>(&mut row_rng, (cfg.lb as f64 * cfg.lag).round() as u32);
+                    let offset_x = offset::<u32, u32, _>(&mut row_rng, cfg.std_offset);
+                    let [r, _, _, a] =
+                        sample_pixel(img, x as f64 - lr as f64 + offset_x as f64, y as f64, bounds, cfg)
+                            .data;
+
+                    let (b, g) = (
+                        sample_pixel(img, x as f64 + lg as f64, y as f64, bounds, cfg).data[1],
+                        sample_pixel(img, x as f64 + lb as f64 + offset_x as f64, y as f64, bounds, cfg)
+                            .data[2],
+                    );
+                    let idx = x as usize * 4;
+                    row[idx..idx + 4].copy_from_slice(&[
+                        brighten_pixels(r, cfg.brighteness_addition),
+                        brighten_pixels(g, cfg.brighteness_addition),
+                        brighten_pixels(b, cfg.brighteness_addition),
+                        brighten_pixels(a, cfg.brighteness_addition),
+                    ]);
+                }
+            });
+        self.primed = true;
+        self
+    }
+    fn chromatic_abberations<R: Rng + ?Sized>(&mut self, rng: &mut R, cfg: &CLI) -> &mut Self {
+        if cfg.chroma_shift {
+            self.chromatic_abberations_chroma(rng, cfg)
+        } else {
+            self.chromatic_abberations_rgb(rng, cfg)
         }
+    }
+    /// Fakes color fringing by sampling the red and blue channels from an
+    /// `offset_x`-shifted coordinate while green stays put
+    fn chromatic_abberations_rgb<R: Rng + ?Sized>(&mut self, rng: &mut R, cfg: &CLI) -> &mut Self {
+        let bounds = self.bounds;
+        let source = self.read_source();
+        let img = &source;
+        let row_stride = bounds.x_max as usize * 4;
+        let offset_x =
+            (Wrapping(cfg.mean_abberation) + Wrapping(offset::<u32, u32, _>(rng, cfg.std_abberation))).0;
+
+        self.buffer
+            .par_chunks_mut(row_stride)
+            .enumerate()
+            .for_each(|(row_idx, row)| {
+                let y = bounds.y_min + row_idx as u32;
+                for x in bounds.x_min..bounds.x_max {
+                    let [r, _, _, a] =
+                        sample_pixel(img, x as f64 + offset_x as f64, y as f64, bounds, cfg).data;
+
+                    let (b, g) = (
+                        sample_pixel(img, x as f64, y as f64, bounds, cfg).data[1],
+                        sample_pixel(img, x as f64 + offset_x as f64, y as f64, bounds, cfg).data[2],
+                    );
+                    let idx = x as usize * 4;
+                    row[idx..idx + 4].copy_from_slice(&[
+                        brighten_pixels(r, cfg.brighteness_addition),
+                        brighten_pixels(g, cfg.brighteness_addition),
+                        brighten_pixels(b, cfg.brighteness_addition),
+                        brighten_pixels(a, cfg.brighteness_addition),
+                    ]);
+                }
+            });
+        self.primed = true;
         self
     }
-    fn chromatic_abberations(&mut self, rng: &mut ThreadRng, cfg: &CLI) -> &mut Self {
-        let offset_x = (Wrapping(cfg.mean_abberation) + Wrapping(offset::<u32, u32>(rng, cfg.std_abberation))).0;
-        for (x, y) in iproduct!(
-            self.bounds.x_min..self.bounds.x_max,
-            self.bounds.y_min..self.bounds.y_max
-        ) {
-            let [r, _, _, a] = self
-                .img
-                .get_pixel(
-                    modified_pixel(x, offset_x, self.bounds.x_max),
-                    modified_pixel(0, y, self.bounds.y_max),
-                )
-                .to_rgba()
-                .data;
-
-            let (b, g) = (
-                self.img
-                    .get_pixel(
-                        modified_pixel(0, x, self.bounds.x_max),
-                        modified_pixel(0, y, self.bounds.y_max),
-                    )
-                    .to_rgba()
-                    .data[1],
-                self.img
-                    .get_pixel(
-                        modified_pixel(x, offset_x, self.bounds.x_max),
-                        modified_pixel(0, y, self.bounds.y_max),
+    /// Keeps luma sharp and displaces only the U/V chroma planes of a BT.601
+    /// YUV conversion, independently per axis, for cleaner photographic
+    /// color-bleed trails than the raw RGB-shift mode
+    fn chromatic_abberations_chroma<R: Rng + ?Sized>(&mut self, rng: &mut R, cfg: &CLI) -> &mut Self {
+        let bounds = self.bounds;
+        let source = self.read_source();
+        let img = &source;
+        let row_stride = bounds.x_max as usize * 4;
+        let offset_x =
+            (Wrapping(cfg.mean_abberation) + Wrapping(offset::<u32, u32, _>(rng, cfg.std_abberation))).0;
+        let offset_y = (Wrapping(cfg.mean_abberation_y.unwrap_or(cfg.mean_abberation))
+            + Wrapping(offset::<u32, u32, _>(
+                rng,
+                cfg.std_abberation_y.unwrap_or(cfg.std_abberation),
+            )))
+        .0;
+
+        self.buffer
+            .par_chunks_mut(row_stride)
+            .enumerate()
+            .for_each(|(row_idx, row)| {
+                let y = bounds.y_min + row_idx as u32;
+                for x in bounds.x_min..bounds.x_max {
+                    let [r, g, b, a] = img.get_pixel(x, y).to_rgba().data;
+                    let (luma, _, _) = rgb_to_yuv(r, g, b);
+
+                    let [sr, sg, sb, _] = sample_pixel(
+                        img,
+                        x as f64 + offset_x as f64,
+                        y as f64 + offset_y as f64,
+                        bounds,
+                        cfg,
                     )
-                    .to_rgba()
-                    .data[2],
-            );
-            self.buffer.put_pixel(
-                x,
-                y,
-                Rgba([
-                    brighten_pixels(r, cfg.brighteness_addition),
-                    brighten_pixels(g, cfg.brighteness_addition),
-                    brighten_pixels(b, cfg.brighteness_addition),
-                    brighten_pixels(a, cfg.brighteness_addition),
-                ]),
-            );
+                    .data;
+                    let (_, u, v) = rgb_to_yuv(sr, sg, sb);
+
+                    let [out_r, out_g, out_b] = yuv_to_rgb(luma, u, v);
+                    let idx = x as usize * 4;
+                    row[idx..idx + 4].copy_from_slice(&[
+                        brighten_pixels(out_r, cfg.brighteness_addition),
+                        brighten_pixels(out_g, cfg.brighteness_addition),
+                        brighten_pixels(out_b, cfg.brighteness_addition),
+                        brighten_pixels(a, cfg.brighteness_addition),
+                    ]);
+                }
+            });
+        self.primed = true;
+        self
+    }
+    /// Last corruption stage, run before `composite`: reduces the buffer to an N-color median-cut palette, for
+    /// a retro/8-bit posterized look on top of the spatial and chromatic
+    /// corruption. With `cfg.dither` on, remaps with Floyd–Steinberg
+    /// error-diffusion rather than a flat nearest-color snap
+    fn quantize(&mut self, cfg: &CLI) -> &mut Self {
+        let palette_size = match cfg.palette_size {
+            Some(n) if n > 0 => n as usize,
+            _ => return self,
+        };
+        let width = self.bounds.x_max as usize;
+        let height = self.bounds.y_max as usize;
+
+        let mut channels: Vec<[i32; 3]> = self
+            .buffer
+            .pixels()
+            .map(|p| {
+                let [r, g, b, _] = p.to_rgba().data;
+                [r as i32, g as i32, b as i32]
+            })
+            .collect();
+        let palette = median_cut_palette(&channels, palette_size);
+
+        for y in 0..height {
+            for x in 0..width {
+                let idx = y * width + x;
+                let old = channels[idx];
+                let new = nearest_palette_color(old, &palette);
+
+                if cfg.dither == DitherMode::On {
+                    let error = [
+                        old[0] - new[0] as i32,
+                        old[1] - new[1] as i32,
+                        old[2] - new[2] as i32,
+                    ];
+                    let mut diffuse = |dx: isize, dy: isize, weight: i32| {
+                        let (nx, ny) = (x as isize + dx, y as isize + dy);
+                        if nx >= 0 && nx < width as isize && ny >= 0 && ny < height as isize {
+                            let neighbor = ny as usize * width + nx as usize;
+                            for c in 0..3 {
+                                channels[neighbor][c] =
+                                    (channels[neighbor][c] + error[c] * weight / 16).clamp(0, 255);
+                            }
+                        }
+                    };
+                    diffuse(1, 0, 7);
+                    diffuse(-1, 1, 3);
+                    diffuse(0, 1, 5);
+                    diffuse(1, 1, 1);
+                }
+
+                let alpha = self.buffer.get_pixel(x as u32, y as u32).to_rgba().data[3];
+                self.buffer
+                    .put_pixel(x as u32, y as u32, Rgba([new[0], new[1], new[2], alpha]));
+            }
         }
         self
     }
+    /// Composites `buffer` — now the product of every chained distortion
+    /// stage, since each one samples the previous stage's output rather than
+    /// the pristine source — back over the pristine original using
+    /// `cfg.blend_mode`, then lerps between the original and that blend by
+    /// `cfg.opacity`. This is what actually lets the corruption passes stack:
+    /// without the stages chaining into one another, there would be nothing
+    /// but the last stage's output to composite
+    fn composite(&mut self, cfg: &CLI) -> &mut Self {
+        let bounds = self.bounds;
+        let img = &self.img;
+        let row_stride = bounds.x_max as usize * 4;
+        let mode = cfg.blend_mode;
+        let opacity = cfg.opacity.clamp(0., 1.);
+
+        self.buffer
+            .par_chunks_mut(row_stride)
+            .enumerate()
+            .for_each(|(row_idx, row)| {
+                let y = bounds.y_min + row_idx as u32;
+                for x in bounds.x_min..bounds.x_max {
+                    let idx = x as usize * 4;
+                    let corrupted = [row[idx], row[idx + 1], row[idx + 2], row[idx + 3]];
+                    let [or_, og, ob, oa] = img.get_pixel(x, y).to_rgba().data;
+
+                    let blended = [
+                        blend_channel(mode, or_, corrupted[0]),
+                        blend_channel(mode, og, corrupted[1]),
+                        blend_channel(mode, ob, corrupted[2]),
+                    ];
+                    row[idx] = lerp_u8(or_, blended[0], opacity);
+                    row[idx + 1] = lerp_u8(og, blended[1], opacity);
+                    row[idx + 2] = lerp_u8(ob, blended[2], opacity);
+                    row[idx + 3] = lerp_u8(oa, corrupted[3], opacity);
+                }
+            });
+        self
+    }
     fn write(&self, path: PathBuf) -> std::io::Result<()> {
         self.buffer.save(path)
     }
 }
 
+/// Inserts a zero-padded frame number before the extension of `base`, e.g.
+/// `out.png` with frame `3` of `120` becomes `out_003.png`
+fn numbered_frame_path(base: &std::path::Path, frame: u32, frame_count: u32) -> PathBuf {
+    let width = frame_count.saturating_sub(1).to_string().len().max(3);
+    let stem = base
+        .file_stem()
+        .and_then(|s| s.to_str())
+        .unwrap_or("frame");
+    let mut path = base.to_path_buf();
+    match base.extension().and_then(|s| s.to_str()) {
+        Some(ext) => path.set_file_name(format!("{}_{:0width$}.{}", stem, frame, ext, width = width)),
+        None => path.set_file_name(format!("{}_{:0width$}", stem, frame, width = width)),
+    }
+    path
+}
+
+/// Encodes a sequence of rendered frames as a looping animated GIF
+fn write_gif(frames: Vec<RgbaImage>, path: PathBuf) -> std::io::Result<()> {
+    let file = std::fs::File::create(path)?;
+    let mut encoder = image::gif::Encoder::new(file);
+    encoder
+        .encode_frames(frames.into_iter().map(image::Frame::new))
+        .expect("failed to encode GIF frames");
+    Ok(())
+}
+
 fn main() -> std::io::Result<()> {
     // Parse options from CLI
     let cli_options = CLI::from_args();
-    let mut cruster = Corrupter::new(&cli_options.image);
-    let mut rng = rand::thread_rng();
-    cruster
-        .dissolve_block(&mut rng, &cli_options)
-        .random_brightening(&mut rng, &cli_options)
-        .chromatic_abberations(&mut rng, &cli_options)
-        .write(cli_options.output)
+    let seed = cli_options.seed.unwrap_or_else(|| rand::thread_rng().gen());
+    if cli_options.seed.is_none() {
+        println!("Using seed: {}", seed);
+    }
+    let mut rng = StdRng::seed_from_u64(seed);
+    let src_img = image::open(&cli_options.image).expect("No compatible image found");
+
+    if cli_options.frames <= 1 {
+        return Corrupter::from_image(src_img)
+            .distort(&mut rng, &cli_options)
+            .random_brightening(&mut rng, &cli_options)
+            .chromatic_abberations(&mut rng, &cli_options)
+            .quantize(&cli_options)
+            .composite(&cli_options)
+            .write(cli_options.output);
+    }
+
+    let mut rendered_frames = Vec::with_capacity(cli_options.frames as usize);
+    for frame in 0..cli_options.frames {
+        let t = frame as f64 / (cli_options.frames - 1) as f64;
+        let mut frame_cfg = cli_options.clone();
+        frame_cfg.block_offset = ramped_u32(cli_options.block_offset, cli_options.block_offset_end, t);
+        frame_cfg.lag = ramped_f64(cli_options.lag, cli_options.lag_end, t);
+        frame_cfg.mean_abberation =
+            ramped_u32(cli_options.mean_abberation, cli_options.mean_abberation_end, t);
+
+        let mut cruster = Corrupter::from_image(src_img.clone());
+        cruster
+            .distort(&mut rng, &frame_cfg)
+            .random_brightening(&mut rng, &frame_cfg)
+            .chromatic_abberations(&mut rng, &frame_cfg)
+            .quantize(&frame_cfg)
+            .composite(&frame_cfg);
+
+        if cli_options.gif {
+            rendered_frames.push(cruster.buffer.clone());
+        } else {
+            cruster.write(numbered_frame_path(
+                &cli_options.output,
+                frame,
+                cli_options.frames,
+            ))?;
+        }
+    }
+
+    if cli_options.gif {
+        write_gif(rendered_frames, cli_options.output)?;
+    }
+    Ok(())
 }